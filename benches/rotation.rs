@@ -7,12 +7,13 @@ use std::sync::mpsc::sync_channel;
 use std::sync::Arc;
 use std::thread;
 use transrust_lib::compute::*;
+use transrust_lib::sink::{run_sink, FileSink};
 use transrust_lib::utils::as_filter;
 
 pub fn rotation_bench(c: &mut Criterion) {
     let mut buf = BufReader::new(File::open("benches/g8.g6").unwrap());
     let nthreads = rayon::current_num_threads();
-    let v = read_graphs(&mut buf, 10000);
+    let v = read_graphs(&mut buf, 10000).unwrap();
     let red_client =
         redis::Client::open("redis://127.0.0.1/").expect("Could not connect to redis.");
     let deftest = |ref x: &GraphTransformation| -> Result<String, ()> {
@@ -26,7 +27,8 @@ pub fn rotation_bench(c: &mut Criterion) {
             |v| {
                 let (snd, rcv) = sync_channel::<LogInfo>(2 * nthreads);
                 let whandle = thread::spawn(move || {
-                    output(rcv, "/dev/null".to_string(), 2000000, false)
+                    let mut sink = FileSink::new("/dev/null", 2000000, false, None)?;
+                    run_sink(rcv, &mut sink, 0, 0)
                 });
                 handle_graphs(
                     v,
@@ -35,7 +37,7 @@ pub fn rotation_bench(c: &mut Criterion) {
                     Arc::new(deftest),
                     false,
                     &red_client,
-                    false,
+                    &HandleOptions::default(),
                 )
                 .unwrap();
                 drop(snd);
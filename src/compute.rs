@@ -1,62 +1,261 @@
 use crate::errors::*;
 use crate::transformation::*;
-use crate::utils::plural;
 use graph::format::from_g6;
 use graph::nauty::canon_graph;
 use graph::transfo_result::GraphTransformation;
-use graph::GraphNauty;
-use log::{info, warn};
+use graph::{GraphIter, GraphNauty};
+use log::warn;
 use rayon::prelude::*;
+use rayon::ThreadPool;
 use redis::Commands;
-use std::convert::From;
-use std::fs::OpenOptions;
-use std::io::{stdout, BufRead, BufWriter, Write};
-use std::sync::mpsc::{Receiver, SendError, Sender, SyncSender};
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashSet};
+use std::convert::{From, TryFrom};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, ErrorKind};
+use std::sync::mpsc::{SendError, Sender, SyncSender};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
-use std::time::Instant;
 
 pub fn apply_filters<F>(g: &GraphTransformation, ftrs: Arc<F>) -> Result<String, ()>
 where
-    F: Fn(&GraphTransformation) -> Result<String, ()>,
+    F: Fn(&GraphTransformation) -> Result<String, ()> + ?Sized,
 {
     ftrs(g)
 }
 
 /// Applying transformations to the graph g.
-pub fn apply_transfos<T>(g: &GraphNauty, trs: &T) -> Vec<GraphTransformation>
+/// Each result comes with the index of the transformation that produced it.
+pub fn apply_transfos<T>(g: &GraphNauty, trs: &T) -> Vec<(usize, GraphTransformation)>
 where
     T: Transformation,
 {
-    let mut r = trs.apply(&g);
-    for rg in r.iter_mut() {
+    let mut r = trs.apply_indexed(&g);
+    for (_, rg) in r.iter_mut() {
         rg.canon();
     }
     r
 }
 
+/// Applies the transformations with the given names to the graph g and returns the results, without
+/// going through the thread pool and the output. Fails if a name is unknown.
+pub fn run_transformations(
+    g: &GraphNauty,
+    transfos: &[&str],
+) -> Result<Vec<GraphTransformation>, TransProofError> {
+    let trs = transfos_from_names(transfos)?;
+    Ok(apply_transfos(g, &trs).into_iter().map(|(_, r)| r).collect())
+}
+
+/// Applies the transformations with the given names repeatedly, at most depth times, starting from
+/// the graph g. Returns every distinct graph reached, except g itself, in canonical form along with
+/// the smallest number of transformations needed to reach it.
+pub fn explore(
+    g: &GraphNauty,
+    transfos: &[&str],
+    depth: usize,
+) -> Result<Vec<(usize, GraphNauty)>, TransProofError> {
+    let trs = transfos_from_names(transfos)?;
+    let mut seen = HashSet::new();
+    seen.insert(format!("{}", canon_graph(g).0));
+    let mut frontier = vec![g.clone()];
+    let mut reached = Vec::new();
+    for level in 1..=depth {
+        let mut next = Vec::new();
+        for h in frontier.iter() {
+            for r in trs.apply(h) {
+                let c = canon_graph(&r.final_graph()).0;
+                if seen.insert(format!("{}", c)) {
+                    next.push(c);
+                }
+            }
+        }
+        if next.is_empty() {
+            break;
+        }
+        reached.extend(next.iter().map(|c| (level, c.clone())));
+        frontier = next;
+    }
+    Ok(reached)
+}
+
+fn transfos_from_names(transfos: &[&str]) -> Result<TransfoVec, TransProofError> {
+    transfos
+        .iter()
+        .map(|&name| <Box<dyn Transformation>>::try_from(name))
+        .collect()
+}
+
+/// Format of the results sent to the output.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// Csv ready to import in a postgresql table.
+    Postgres,
+    /// One json object per line, see TransfoRecord.
+    Jsonl,
+}
+
+impl TryFrom<&str> for OutputFormat {
+    type Error = TransProofError;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        match input.trim().to_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "postgres" => Ok(OutputFormat::Postgres),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            s => Err(TransProofError::UnknownOutputFormat(s.to_string())),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Name of the format, as given to --output-format.
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Postgres => "postgres",
+            OutputFormat::Jsonl => "jsonl",
+        }
+    }
+
+    /// Extension of the files holding results in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Csv | OutputFormat::Postgres => "csv",
+            OutputFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+/// Result as written by the jsonl output format.
+#[derive(Debug, Serialize)]
+pub struct TransfoRecord<'a> {
+    /// Name of the transformation.
+    pub transformation: &'a str,
+    /// Graph6 signature of the input graph.
+    pub init: String,
+    /// Canonical graph6 signature of the resulting graph.
+    pub result: String,
+}
+
+impl<'a> TransfoRecord<'a> {
+    /// h has to be canonized, as done by apply_transfos.
+    pub fn new(transformation: &'a str, g: &GraphNauty, h: &GraphTransformation) -> Self {
+        TransfoRecord {
+            transformation,
+            init: format!("{}", g),
+            result: format!("{}", h.final_graph()),
+        }
+    }
+}
+
+/// Options on how the results of the transformations are handled.
+#[derive(Debug, Default)]
+pub struct HandleOptions {
+    pub format: OutputFormat,
+    /// Names of the transformations, in the order in which they are applied.
+    pub names: Vec<String>,
+    /// Hashes of the results already sent if duplicated results should be dropped.
+    pub dedup: Option<Mutex<HashSet<u64>>>,
+    /// Maximum number of results to send. A value of 0 means no limit.
+    pub max_results: usize,
+    /// Sends the results without formatting them, when they are only counted.
+    pub count_only: bool,
+    /// Number of results sent so far.
+    pub sent: AtomicUsize,
+    /// Number of distinct results sent so far if duplicated results are dropped.
+    pub distinct: AtomicUsize,
+    /// Set to stop handling the remaining graphs, e.g. when the user interrupts the program.
+    pub stop: Arc<AtomicBool>,
+    /// Thread pool in which the graphs are handled. If None, handle_graphs builds a pool with a
+    /// thread per core for each call.
+    pub pool: Option<ThreadPool>,
+}
+
+impl HandleOptions {
+    /// Returns true if the result was not seen before or if deduplication is disabled.
+    /// h has to be canonized, as done by apply_transfos.
+    fn is_new(&self, h: &GraphTransformation) -> Result<bool, TransProofError> {
+        match &self.dedup {
+            Some(seen) => {
+                let mut hasher = DefaultHasher::new();
+                format!("{}", h.final_graph()).hash(&mut hasher);
+                let mut seen = seen.lock().map_err(|_| TransProofError::PoisonedDedup)?;
+                Ok(seen.insert(hasher.finish()))
+            }
+            None => Ok(true),
+        }
+    }
+
+    /// Reserves a slot for a result. Returns false if the maximum number of results is reached.
+    fn reserve(&self) -> bool {
+        if self.max_results == 0 {
+            self.sent.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+        // Never goes past the maximum so that sent stays the number of results sent.
+        self.sent
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                if n < self.max_results {
+                    Some(n + 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
+    }
+
+    /// Returns true if no more results should be sent.
+    pub fn is_full(&self) -> bool {
+        self.max_results > 0 && self.sent.load(Ordering::Relaxed) >= self.max_results
+    }
+
+    /// Returns true if the remaining graphs should not be handled.
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+}
+
 /// Should apply a set of transformations, filter the graphs and return the result
 pub fn handle_graph<T, F>(
     g: GraphNauty,
     t: &mut SenderVariant<LogInfo>,
     trsf: &T,
     ftrs: Arc<F>,
-    postgres: bool
+    opts: &HandleOptions,
 ) -> Result<(), TransProofError>
 where
     T: Transformation,
-    F: Fn(&GraphTransformation) -> Result<String, ()>,
+    F: Fn(&GraphTransformation) -> Result<String, ()> + ?Sized,
 {
+    if opts.is_full() || opts.is_stopped() {
+        return Ok(());
+    }
     let mut r = apply_transfos(&g, trsf);
-    for h in r {
+    for (transfo, h) in r {
         let s = apply_filters(&h, ftrs.clone());
-        if let Ok(res) = s {
-            let txt = if postgres {
-                h.to_postgres()
-            } else {
-                h.tocsv()
+        if s.is_ok() && opts.is_new(&h)? {
+            if !opts.reserve() {
+                break;
+            }
+            if opts.dedup.is_some() {
+                opts.distinct.fetch_add(1, Ordering::Relaxed);
+            }
+            let txt = match opts.format {
+                _ if opts.count_only => String::new(),
+                OutputFormat::Csv => h.tocsv(),
+                OutputFormat::Postgres => h.to_postgres(),
+                OutputFormat::Jsonl => {
+                    let name = opts.names.get(transfo).map_or("", String::as_str);
+                    serde_json::to_string(&TransfoRecord::new(name, &g, &h))?
+                }
             };
-            t.send(LogInfo::Transfo(h, txt.to_string()))?;
+            t.send(LogInfo::Transfo(h, txt.to_string(), transfo))?;
         }
     }
     Ok(())
@@ -68,11 +267,15 @@ pub fn handle_graph_with_filter<T, F>(
     trsf: &T,
     ftrs: Arc<F>,
     red_con: &mut Arc<Mutex<redis::Connection>>,
+    opts: &HandleOptions,
 ) -> Result<(), TransProofError>
 where
     T: Transformation,
-    F: Fn(&GraphTransformation) -> Result<String, ()>,
+    F: Fn(&GraphTransformation) -> Result<String, ()> + ?Sized,
 {
+    if opts.is_stopped() {
+        return Ok(());
+    }
     let mut r = apply_transfos(&g, trsf);
     if !r.is_empty() {
         let mut sig = format!("{}", g);
@@ -81,12 +284,17 @@ where
         let mut pipe = redis::pipe();
         pipe.hget(&sig[&sig.len() - 2..], &sig);
         let mut fg;
-        for res in r.iter_mut() {
+        for (_, res) in r.iter_mut() {
             fg = canon_graph(&res.final_graph());
             sig = format!("{}", fg.0);
             pipe.hget(&sig[&sig.len() - 2..], &sig);
         }
-        let vals: Vec<f64> = pipe.query(&mut *red_con.lock().unwrap()).unwrap();
+        let vals: Vec<f64> = {
+            let mut con = red_con
+                .lock()
+                .map_err(|_| TransProofError::PoisonedConnection)?;
+            pipe.query(&mut *con)?
+        };
         if vals.len() == 1 {
             eprintln!("{}", psig);
         }
@@ -98,9 +306,10 @@ where
         if filtered.len() == tot_trans {
             t.send(LogInfo::LocalExtremum(g))?;
         } else {
-            for (id, g) in filtered {
+            for (id, (transfo, g)) in filtered {
                 t.send(LogInfo::IncorrectTransfo {
                     result: g.clone(),
+                    transfo: *transfo,
                     before: vals[0],
                     after: vals[id + 1],
                 })?
@@ -118,34 +327,42 @@ pub fn handle_graphs<T, F>(
     ftrs: Arc<F>,
     filter: bool,
     red_client: &redis::Client,
-    postgres: bool
+    opts: &HandleOptions,
 ) -> Result<(), TransProofError>
 where
     T: Transformation,
-    F: Fn(&GraphTransformation) -> Result<String, ()> + Send + Sync,
+    F: Fn(&GraphTransformation) -> Result<String, ()> + Send + Sync + ?Sized,
 {
-    if filter {
-        let red_con = Arc::new(Mutex::new(red_client.get_connection().expect("Could not connect to redis.")));
-        v.into_par_iter().try_for_each_with((t, red_con), |s, x| {
-            handle_graph_with_filter(x, &mut s.0, trsf, ftrs.clone(), &mut s.1)
-        })?;
-    } else {
-        v.into_par_iter().try_for_each_with(t, |mut s, x| {
-            handle_graph(x, &mut s, trsf, ftrs.clone(), postgres)
-        })?;
+    let run = || -> Result<(), TransProofError> {
+        if filter {
+            let red_con = Arc::new(Mutex::new(red_client.get_connection()?));
+            v.into_par_iter().try_for_each_with((t, red_con), |s, x| {
+                handle_graph_with_filter(x, &mut s.0, trsf, ftrs.clone(), &mut s.1, opts)
+            })?;
+        } else {
+            v.into_par_iter().try_for_each_with(t, |mut s, x| {
+                handle_graph(x, &mut s, trsf, ftrs.clone(), opts)
+            })?;
+        }
+        Ok(())
+    };
+    match opts.pool {
+        Some(ref pool) => pool.install(run),
+        None => rayon::ThreadPoolBuilder::new().build()?.install(run),
     }
-    Ok(())
 }
 
 /// Read files of graphs
 /// (file of sigs)
-pub fn read_graphs<F>(rdr: &mut F, batchsize: usize) -> Vec<GraphNauty>
+/// Empty lines and lines that are not valid UTF-8 are skipped, other reading errors are returned.
+pub fn read_graphs<F>(rdr: &mut F, batchsize: usize) -> Result<Vec<GraphNauty>, TransProofError>
 where
     F: BufRead,
 {
     let mut t = Vec::with_capacity(batchsize);
     for l in rdr.lines().by_ref().take(batchsize) {
         match l {
+            Ok(ref sig) if sig.is_empty() => {}
             Ok(sig) => match from_g6(&sig) {
                 Ok(g) => {
                     t.push(g);
@@ -154,79 +371,79 @@ where
                     warn!("Wrong input : {}", e);
                 }
             },
-            Err(e) => {
+            Err(ref e) if e.kind() == ErrorKind::InvalidData => {
                 warn!("{}", e);
             }
+            Err(e) => {
+                return Err(e.into());
+            }
+        }
+    }
+    Ok(t)
+}
+
+/// Distribution of the number of vertices and edges of a set of graphs.
+#[derive(Debug, Default)]
+pub struct GraphStats {
+    pub graphs: usize,
+    pub vertices: BTreeMap<u64, usize>,
+    pub edges: BTreeMap<u64, usize>,
+}
+
+impl GraphStats {
+    pub fn add(&mut self, g: &GraphNauty) {
+        self.graphs += 1;
+        *self.vertices.entry(g.order()).or_insert(0) += 1;
+        *self.edges.entry(g.size()).or_insert(0) += 1;
+    }
+}
+
+impl fmt::Display for GraphStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Graphs : {}", self.graphs)?;
+        writeln!(f, "Vertices :")?;
+        for (n, count) in self.vertices.iter() {
+            writeln!(f, "    {} : {}", n, count)?;
+        }
+        writeln!(f, "Edges :")?;
+        for (m, count) in self.edges.iter() {
+            writeln!(f, "    {} : {}", m, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads all the graphs and computes their statistics without applying any transformation.
+pub fn graph_stats<F>(rdr: &mut F, batchsize: usize) -> Result<GraphStats, TransProofError>
+where
+    F: BufRead,
+{
+    let mut stats = GraphStats::default();
+    loop {
+        let v = read_graphs(rdr, batchsize)?;
+        if v.is_empty() {
+            break;
+        }
+        for g in v.iter() {
+            stats.add(g);
         }
     }
-    t
+    Ok(stats)
 }
 
 #[derive(Debug)]
 pub enum LogInfo {
-    Transfo(GraphTransformation, String),
+    /// A result, its formatted output and the index of the transformation that produced it.
+    Transfo(GraphTransformation, String, usize),
     IncorrectTransfo {
         result: GraphTransformation,
+        transfo: usize,
         before: f64,
         after: f64,
     },
     LocalExtremum(GraphNauty),
 }
 
-pub fn output(
-    receiver: Receiver<LogInfo>,
-    filename: String,
-    buffer: usize,
-    append: bool,
-) -> Result<(), TransProofError> {
-    let mut bufout: Box<dyn Write> = match filename.as_str() {
-        "-" => Box::new(BufWriter::with_capacity(buffer, stdout())),
-        _ => Box::new(BufWriter::with_capacity(
-            buffer,
-            OpenOptions::new()
-                .write(true)
-                .append(append)
-                .create(true)
-                .open(filename)?,
-        )),
-    };
-    let start = Instant::now();
-    let mut i = 0;
-    for log in receiver.iter() {
-        match log {
-            LogInfo::Transfo(t, s) => {
-                i += 1;
-                bufout.write_all(&s.into_bytes())?;
-                bufout.write_all(&['\n' as u8])?;
-            }
-            LogInfo::IncorrectTransfo {
-                result: g,
-                before: v1,
-                after: v2,
-            } => {
-                i += 1;
-                bufout.write_all(&format!("{}", g.to_incorrect()).into_bytes())?;
-                bufout.write_all(&format!(",{},{}\n",v1,v2).into_bytes())?;
-            }
-            LogInfo::LocalExtremum(g) => {
-                bufout.write_all(&format!("{}\n", g).into_bytes())?;
-            }
-        }
-    }
-    let duration = start.elapsed();
-    info!("Done : {} transformation{}", i, plural(i));
-    let secs = duration.as_secs() as usize;
-    let millis = (duration.subsec_nanos() as usize) / (1e6 as usize);
-    info!(
-        "Took {} second{} and {} millisecond{}",
-        secs,
-        plural(secs),
-        millis,
-        plural(millis)
-    );
-    Ok(())
-}
-
 //#[derive(Clone)]
 pub enum SenderVariant<T>
 where
@@ -277,3 +494,80 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Canonical signature of the graph with the given graph6 signature.
+    fn canon_sig(sig: &str) -> String {
+        format!("{}", canon_graph(&from_g6(&sig.to_string()).unwrap()).0)
+    }
+
+    /// Level and signature of the graphs reached by explore.
+    fn explored(g: &GraphNauty, transfos: &[&str], depth: usize) -> Vec<(usize, String)> {
+        explore(g, transfos, depth)
+            .unwrap()
+            .into_iter()
+            .map(|(level, h)| (level, format!("{}", h)))
+            .collect()
+    }
+
+    #[test]
+    fn explore_levels() {
+        let triangle = from_g6(&"Bw".to_string()).unwrap();
+        assert!(explored(&triangle, &["remove_edge"], 0).is_empty());
+        // Every edge removal leads to the same graph, which is only reported once.
+        assert_eq!(
+            explored(&triangle, &["remove_edge"], 2),
+            vec![(1, canon_sig("Bg")), (2, canon_sig("B_"))]
+        );
+        // Stops once no new graph is reached.
+        assert_eq!(
+            explored(&triangle, &["remove_edge"], 10),
+            vec![(1, canon_sig("Bg")), (2, canon_sig("B_")), (3, canon_sig("B?"))]
+        );
+    }
+
+    #[test]
+    fn explore_skips_seen_graphs() {
+        // Adding and removing edges from a path on 3 vertices leads to the triangle, to an edge
+        // with an isolated vertex and to the empty graph, the path itself being never reported.
+        let path = from_g6(&"Bg".to_string()).unwrap();
+        let reached = explored(&path, &["add_edge", "remove_edge"], 3);
+        let mut sigs = reached.iter().map(|(_, s)| s.clone()).collect::<Vec<_>>();
+        sigs.sort();
+        let mut expected = vec![canon_sig("Bw"), canon_sig("B_"), canon_sig("B?")];
+        expected.sort();
+        assert_eq!(sigs, expected);
+        for (level, sig) in reached {
+            let expected = if sig == canon_sig("B?") { 2 } else { 1 };
+            assert_eq!(level, expected);
+        }
+    }
+
+    #[test]
+    fn run_transformations_results() {
+        let triangle = from_g6(&"Bw".to_string()).unwrap();
+        let results = run_transformations(&triangle, &["remove_edge", "add_vertex"]).unwrap();
+        assert!(!results.is_empty());
+        let sigs = results
+            .iter()
+            .map(|h| format!("{}", h.final_graph()))
+            .collect::<HashSet<_>>();
+        // A path on 3 vertices and a triangle with an isolated vertex.
+        let expected = vec![canon_sig("Bg"), canon_sig("Cw")]
+            .into_iter()
+            .collect::<HashSet<_>>();
+        assert_eq!(sigs, expected);
+    }
+
+    #[test]
+    fn run_transformations_unknown_name() {
+        let triangle = from_g6(&"Bw".to_string()).unwrap();
+        assert!(matches!(
+            run_transformations(&triangle, &["remove_edge", "Unknown"]),
+            Err(TransProofError::UnknownTransformation(ref name)) if name == "unknown"
+        ));
+    }
+}
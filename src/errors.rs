@@ -15,6 +15,28 @@ pub enum TransProofError {
     Thread(Box<dyn Any + Send>),
     #[error(transparent)]
     ThreadPool(#[from] rayon::ThreadPoolBuildError),
+    #[error(transparent)]
+    Signal(#[from] ctrlc::Error),
     #[error("Unknown transformation: {0}.")]
     UnknownTransformation(String),
+    #[error(transparent)]
+    Redis(#[from] redis::RedisError),
+    #[error("Redis connection lock poisoned.")]
+    PoisonedConnection,
+    #[error("Deduplication set lock poisoned.")]
+    PoisonedDedup,
+    #[error("Unknown compression format: {0}.")]
+    UnknownCompression(String),
+    #[error("Unknown output format: {0}.")]
+    UnknownOutputFormat(String),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid filter: {0}.")]
+    InvalidFilter(String),
+    #[error("--output-dir cannot be used with --output.")]
+    ConflictingOutputs,
+    #[error("--postgres cannot be used with another --output-format.")]
+    ConflictingFormats,
+    #[error("--header cannot be used with {0}.")]
+    ConflictingHeader(String),
 }
@@ -1,4 +1,5 @@
 pub mod compute;
 pub mod errors;
+pub mod sink;
 pub mod transformation;
 pub mod utils;
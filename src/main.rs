@@ -1,25 +1,24 @@
-pub mod compute;
-mod errors;
-mod transformation;
-mod utils;
-
-use graph::transfo_result::GraphTransformation;
 use graph::GraphNauty;
 // use graph::invariant;
 use docopt::Docopt;
 use log::{debug, info, warn, error};
 use serde::Deserialize;
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::{stdin, BufRead, BufReader};
+use std::path::Path;
 use std::sync::mpsc::{channel, sync_channel};
-use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
 use std::thread;
-use std::convert::TryInto;
+use std::convert::TryFrom;
+use time::OffsetDateTime;
 
-use compute::*;
-use errors::*;
-use transformation::*;
-use utils::*;
+use transrust_lib::compute::*;
+use transrust_lib::errors::*;
+use transrust_lib::sink::*;
+use transrust_lib::transformation::*;
+use transrust_lib::utils::*;
 
 // (-f <filter>)...
 // -f <filter>            The filters \
@@ -32,8 +31,9 @@ These graphs have to be given in graph6 format from the input (one signature per
 result is outputed in csv format.
 
 Usage:
-    transrust [options] remove <e>
-    transrust [options] <transformations>...
+    transrust [options] [(-k <filter>)...] remove <e>
+    transrust [options] stats
+    transrust [options] [(-k <filter>)...] <transformations>...
     transrust (-h | --help)
     transrust --transfos
 
@@ -42,9 +42,12 @@ Options:
     -v, --verbose          Shows more information.
     --transfos             Shows a list of available transformations.
     -i, --input <input>    File containing the graph6 signatures. Uses the standard input if '-'.
+                           If it is a directory, all the files it contains are read.
                            [default: -]
     -o, --output <output>  File where to write the result. Uses the standard output if '-'.
                            [default: -]
+    --output-dir <dir>     Directory where to write each result in its own file, named after the
+                           signature of the resulting graph. Cannot be used with --output.
     -b, --batch <batch>    Batch size [default: 1000000]
     -s, --buffer <buffer>  Size of the buffer [default: 2000000000]
     -t <threads>           Number of threads to be used for computation. A value of 0 means using
@@ -55,7 +58,37 @@ Options:
                            [default: 0]
     -a, --append           Does not overwrite output file but appends results instead.
     -f, --filter           Only outputs incorrect transfos.
-    --postgres             Format as a csv ready to import in a postgresql table.
+    --output-format <fmt>  Format of the results: csv, postgres (a csv ready to import in a
+                           postgresql table) or jsonl (one json object per line with the name of
+                           the transformation and the signatures of the input and resulting
+                           graphs). Ignored with --filter. Uses csv if not given.
+    --postgres             Same as --output-format postgres. Cannot be used with another format.
+    --dedup                Only outputs the first result leading to a given graph. Ignored with
+                           --filter.
+    -m, --max-results <n>  Stops after outputting n results. A value of 0 means no limit. Ignored
+                           with --filter. [default: 0]
+    -k, --keep <filter>    Only outputs the results satisfying the filter. Can be repeated, in
+                           which case results have to satisfy all the filters. Available filters
+                           are min_vertices:<n>, max_vertices:<n>, min_edges:<m> and max_edges:<m>.
+    --compress <format>    Compress the output file using gzip or zstd. If not given, the format is
+                           guessed from the extension (.gz or .zst) of the output file. Ignored
+                           when writing to the standard output.
+    --max-input-bytes <n>  Maximum number of bytes to read from the input. Reading stops with an
+                           error if the input is larger. A value of 0 means no limit. [default: 0]
+    --dry-run              Only counts the results of each transformation without writing them.
+    --progress-interval <n>
+                           Logs the number of transformations handled every n transformations. A
+                           value of 0 disables it. [default: 10000]
+    --flush-interval <ms>  Flushes the output at least every ms milliseconds, even if the buffer is
+                           not full. A value of 0 means only flushing when the buffer is full.
+                           [default: 0]
+    --header               Starts the output with a line beginning with '#' giving the version, the
+                           transformations, the input, the format and the date of the run.
+                           Only available for csv results, and not with --output-dir or
+                           --dry-run.
+    --stats <file>         Writes the statistics of the run (number of results per transformation,
+                           number of distinct results with --dedup and duration) to the file as
+                           json.
     ";
 
 #[derive(Debug, Deserialize, Clone)]
@@ -64,6 +97,7 @@ struct Args {
     flag_transfos: bool,
     flag_i: String,
     flag_o: String,
+    flag_output_dir: Option<String>,
     flag_b: usize,
     flag_s: usize,
     arg_transformations: Vec<String>,
@@ -71,19 +105,34 @@ struct Args {
     flag_c: usize,
     flag_append: bool,
     cmd_remove: bool,
+    cmd_stats: bool,
     arg_e: Option<u64>,
     flag_f: bool,
     flag_postgres: bool,
+    flag_output_format: Option<String>,
+    flag_dedup: bool,
+    flag_max_results: usize,
+    flag_keep: Vec<String>,
+    flag_compress: Option<String>,
+    flag_max_input_bytes: usize,
+    flag_dry_run: bool,
+    flag_progress_interval: usize,
+    flag_flush_interval: u64,
+    flag_header: bool,
+    flag_stats: Option<String>,
 }
 
-fn init_transfo(lst: &[String]) -> TransfoVec {
-    lst.iter().map(|x| x.as_str().try_into()).inspect(|res| {
+/// Returns the names of the known transformations along with the transformations themselves.
+fn init_transfo(lst: &[String]) -> (Vec<String>, TransfoVec) {
+    lst.iter().map(|x| {
+        <Box<dyn Transformation>>::try_from(x.as_str()).map(|t| (x.trim().to_lowercase(), t))
+    }).inspect(|res| {
         if let Err(e) = res {
             warn!("{}", e);
         }
     })
     .filter_map(Result::ok)
-    .collect()
+    .unzip()
     //if lst.is_empty() {
         //return Vec::new();
     //}
@@ -136,6 +185,10 @@ fn main() -> Result<(), TransProofError> {
 
     let filename = args.flag_i;
     let outfilename = args.flag_o;
+    let outdir = args.flag_output_dir;
+    if outdir.is_some() && outfilename != "-" {
+        return Err(TransProofError::ConflictingOutputs);
+    }
     let batch = args.flag_b;
     let buffer = args.flag_s;
     let transfos = args.arg_transformations;
@@ -143,14 +196,62 @@ fn main() -> Result<(), TransProofError> {
     let channel_size = args.flag_c;
     let append = args.flag_append;
     let cmd_remove = args.cmd_remove;
+    let cmd_stats = args.cmd_stats;
     let arg_e = args.arg_e;
     let flag_f = args.flag_f;
-    let flag_postgres = args.flag_postgres;
+    let format = match args.flag_output_format {
+        Some(format) => OutputFormat::try_from(format.as_str())?,
+        None if args.flag_postgres => OutputFormat::Postgres,
+        None => OutputFormat::Csv,
+    };
+    if args.flag_postgres && format != OutputFormat::Postgres {
+        return Err(TransProofError::ConflictingFormats);
+    }
+    // The incorrect transformations are always written as csv.
+    let out_format = if flag_f { OutputFormat::Csv } else { format };
+    let mut opts = HandleOptions {
+        format,
+        dedup: if args.flag_dedup {
+            Some(Mutex::new(HashSet::new()))
+        } else {
+            None
+        },
+        max_results: args.flag_max_results,
+        count_only: args.flag_dry_run,
+        ..Default::default()
+    };
+    let max_input_bytes = args.flag_max_input_bytes;
+    let dry_run = args.flag_dry_run;
+    let progress = args.flag_progress_interval;
+    let flush_interval = args.flag_flush_interval;
+    let header = args.flag_header;
+    if header {
+        if outdir.is_some() {
+            return Err(TransProofError::ConflictingHeader("--output-dir".to_string()));
+        }
+        if dry_run {
+            return Err(TransProofError::ConflictingHeader("--dry-run".to_string()));
+        }
+        if out_format != OutputFormat::Csv {
+            return Err(TransProofError::ConflictingHeader(format!(
+                "--output-format {}",
+                out_format.name()
+            )));
+        }
+    }
+    let stats_file = args.flag_stats;
+    let compression = match args.flag_compress {
+        Some(format) => Some(OutputCompression::try_from(format.as_str())?),
+        None => OutputCompression::from_extension(&outfilename),
+    };
 
     // Init filters
-    let deftest = Arc::new(|ref x: &GraphTransformation| -> Result<String, ()> {
-        as_filter(|_| true, |_| "".to_string())(&x)
-    });
+    let deftest = all_of(
+        args.flag_keep
+            .iter()
+            .map(|x| parse_filter(x).map(|p| as_filter(p, |_| "".to_string())))
+            .collect::<Result<Vec<_>, _>>()?,
+    );
     //let ftrs = Arc::new(|ref x: &GraphTransformation| -> Result<String, ()> {
         //combine_filters(&deftest, trash_node)(&x)
     //});
@@ -158,13 +259,38 @@ fn main() -> Result<(), TransProofError> {
     // Init input
     let mut buf: Box<dyn BufRead> = match filename.as_str() {
         "-" => Box::new(BufReader::new(stdin())),
-        _ => Box::new(BufReader::new(File::open(filename)?)),
+        _ => open_input(Path::new(&filename))?,
     };
+    if max_input_bytes > 0 {
+        buf = Box::new(LimitedReader::new(buf, max_input_bytes));
+    }
+
+    if cmd_stats {
+        print!("{}", graph_stats(&mut buf, batch)?);
+        return Ok(());
+    }
 
     // Init thread pool
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(num_threads)
-        .build_global()?;
+    opts.pool = Some(
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build()?,
+    );
+
+    // Init transformations
+    let (names, trs): (Vec<String>, TransfoVec) = if !cmd_remove {
+        let (names, trs) = init_transfo(&transfos);
+        if trs.is_empty() {
+            error!("No transformation found.");
+            panic!("No transformation found.");
+        }
+        (names, trs)
+    } else {
+        let mut res: TransfoVec = Vec::new();
+        res.push(Box::new(move |g: &GraphNauty| graph::transfos::remove_num_edges(g, arg_e.unwrap())));
+        (vec!["remove".to_string()], res)
+    };
+    opts.names = names.clone();
 
     // Init comunications with sink thread
     let sender;
@@ -179,43 +305,95 @@ fn main() -> Result<(), TransProofError> {
         receiver = chan.1;
     }
     let builder = thread::Builder::new();
-    let whandle = builder.spawn(move || output(receiver, outfilename, buffer, append))?;
-
-    // Init transformations
-    let trs: TransfoVec = if !cmd_remove {
-        let trs = init_transfo(&transfos);
-        if trs.is_empty() {
-            error!("No transformation found.");
-            panic!("No transformation found.");
-        }
-        trs
+    let whandle = if dry_run {
+        builder.spawn(move || run_sink(receiver, &mut CountSink, progress, 0))?
+    } else if let Some(dir) = outdir {
+        builder.spawn(move || {
+            run_sink(receiver, &mut DirSink::new(&dir, out_format)?, progress, 0)
+        })?
     } else {
-        let mut res: TransfoVec = Vec::new();
-        res.push(Box::new(move |g: &GraphNauty| graph::transfos::remove_num_edges(g, arg_e.unwrap())));
-        res
+        let mut sink = FileSink::new(&outfilename, buffer, append, compression)?;
+        if header {
+            sink.write_header(&format!(
+                "# {} {}, transformations: {}, input: {}, format: {}, date: {}",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION"),
+                match arg_e {
+                    Some(e) if cmd_remove => format!("remove {}", e),
+                    _ => names.join(" "),
+                },
+                filename,
+                out_format.name(),
+                OffsetDateTime::now_utc()
+            ))?;
+        }
+        builder.spawn(move || run_sink(receiver, &mut sink, progress, flush_interval))?
     };
 
-    let red_client = redis::Client::open("redis://127.0.0.1/").expect("Could not connect to redis.");
+    // Stop at the first interruption so that the results already computed are written.
+    let stop = opts.stop.clone();
+    ctrlc::set_handler(move || {
+        if stop.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+        warn!("Interrupted, writing the results computed so far. Interrupt again to exit now.");
+    })?;
+
+    let red_client = redis::Client::open("redis://127.0.0.1/")?;
 
     let mut s = 1;
     let mut total = 0;
     let mut v;
     let mut res = Ok(());
     while s > 0 {
-        v = read_graphs(&mut buf, batch);
+        v = match read_graphs(&mut buf, batch) {
+            Ok(v) => v,
+            Err(e) => {
+                res = Err(e);
+                break;
+            }
+        };
         s = v.len();
         total += s;
         if s > 0 {
             info!("Loaded a batch of size {}", s);
-            res = handle_graphs(v, sender.clone(), &trs, deftest.clone(), flag_f, &red_client, flag_postgres);
+            res = handle_graphs(v, sender.clone(), &trs, deftest.clone(), flag_f, &red_client, &opts);
             if res.is_err() {
                 break;
             }
             info!("Finished a batch of size {} ({} so far)", s, total);
+            if opts.is_full() {
+                info!("Reached the maximum number of results.");
+                break;
+            }
+            if opts.is_stopped() {
+                break;
+            }
         }
     }
     drop(sender);
-    whandle.join().map_err(|x| TransProofError::Thread(x))??;
+    let mut stats = whandle.join().map_err(|x| TransProofError::Thread(x))??;
+    for (id, name) in names.iter().enumerate() {
+        let count = stats.counts.get(id).cloned().unwrap_or(0);
+        info!("{} : {} result{}", name, count, plural(count));
+        *stats.transformations.entry(name.clone()).or_insert(0) += count;
+    }
+    // Counted when sent, the set of seen results also holds those dropped by --max-results.
+    stats.distinct = opts
+        .dedup
+        .as_ref()
+        .map(|_| opts.distinct.load(Ordering::Relaxed));
+    if let Some(distinct) = stats.distinct {
+        info!("{} distinct result{}", distinct, plural(distinct));
+    }
+    if let Some(file) = stats_file {
+        serde_json::to_writer_pretty(File::create(file)?, &stats)?;
+    }
     res?;
+    if opts.is_stopped() {
+        // Lets scripts tell a partial output from a complete one, as the shell does.
+        warn!("Interrupted, the output is incomplete.");
+        std::process::exit(130);
+    }
     Ok(())
 }
@@ -0,0 +1,528 @@
+use crate::compute::{LogInfo, OutputFormat};
+use crate::errors::*;
+use crate::utils::plural;
+use flate2::write::GzEncoder;
+use log::info;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, stdout, BufWriter, ErrorKind, Stdout, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// Destination of the messages sent by the computing threads.
+pub trait ResultSink {
+    /// Handles one message.
+    fn accept(&mut self, log: LogInfo) -> Result<(), TransProofError>;
+
+    /// Writes what has been buffered so far, if anything.
+    fn flush(&mut self) -> Result<(), TransProofError> {
+        Ok(())
+    }
+
+    /// Called once every message has been received.
+    fn finish(&mut self) -> Result<(), TransProofError> {
+        self.flush()
+    }
+}
+
+/// Compression applied to the output file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputCompression {
+    Gzip,
+    Zstd,
+}
+
+impl OutputCompression {
+    /// Guesses the compression from the extension of the output file.
+    pub fn from_extension(filename: &str) -> Option<Self> {
+        if filename.ends_with(".gz") {
+            Some(OutputCompression::Gzip)
+        } else if filename.ends_with(".zst") {
+            Some(OutputCompression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+impl TryFrom<&str> for OutputCompression {
+    type Error = TransProofError;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        match input.trim().to_lowercase().as_str() {
+            "gzip" | "gz" => Ok(OutputCompression::Gzip),
+            "zstd" | "zst" => Ok(OutputCompression::Zstd),
+            s => Err(TransProofError::UnknownCompression(s.to_string())),
+        }
+    }
+}
+
+/// Destination of a FileSink. The encoders are kept apart so that the end of their stream can be
+/// written, and its errors reported, once every result is written.
+enum FileOutput {
+    Stdout(Stdout),
+    Plain(File),
+    Gzip(GzEncoder<File>),
+    Zstd(zstd::Encoder<'static, File>),
+}
+
+impl FileOutput {
+    /// Writes the end of the compressed stream, if any.
+    fn finish(&mut self) -> io::Result<()> {
+        match self {
+            FileOutput::Gzip(e) => e.try_finish(),
+            FileOutput::Zstd(e) => e.do_finish(),
+            FileOutput::Stdout(_) | FileOutput::Plain(_) => Ok(()),
+        }
+    }
+}
+
+impl Write for FileOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            FileOutput::Stdout(w) => w.write(buf),
+            FileOutput::Plain(w) => w.write(buf),
+            FileOutput::Gzip(w) => w.write(buf),
+            FileOutput::Zstd(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            FileOutput::Stdout(w) => w.flush(),
+            FileOutput::Plain(w) => w.flush(),
+            FileOutput::Gzip(w) => w.flush(),
+            FileOutput::Zstd(w) => w.flush(),
+        }
+    }
+}
+
+/// Sink writing the results in a file or in the standard output if the filename is '-'.
+pub struct FileSink {
+    bufout: BufWriter<FileOutput>,
+}
+
+impl FileSink {
+    pub fn new(
+        filename: &str,
+        buffer: usize,
+        append: bool,
+        compression: Option<OutputCompression>,
+    ) -> Result<Self, TransProofError> {
+        let out = match filename {
+            "-" => FileOutput::Stdout(stdout()),
+            _ => {
+                let file = OpenOptions::new()
+                    .write(true)
+                    .append(append)
+                    .create(true)
+                    .open(filename)?;
+                match compression {
+                    Some(OutputCompression::Gzip) => FileOutput::Gzip(GzEncoder::new(
+                        file,
+                        flate2::Compression::default(),
+                    )),
+                    Some(OutputCompression::Zstd) => {
+                        FileOutput::Zstd(zstd::Encoder::new(file, 0)?)
+                    }
+                    None => FileOutput::Plain(file),
+                }
+            }
+        };
+        Ok(FileSink {
+            bufout: BufWriter::with_capacity(buffer, out),
+        })
+    }
+
+    /// Writes a line describing the run before the results.
+    pub fn write_header(&mut self, header: &str) -> Result<(), TransProofError> {
+        self.bufout.write_all(header.as_bytes())?;
+        self.bufout.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+impl ResultSink for FileSink {
+    fn accept(&mut self, log: LogInfo) -> Result<(), TransProofError> {
+        match log {
+            LogInfo::Transfo(_, s, _) => {
+                self.bufout.write_all(&s.into_bytes())?;
+                self.bufout.write_all(b"\n")?;
+            }
+            LogInfo::IncorrectTransfo {
+                result: g,
+                before: v1,
+                after: v2,
+                ..
+            } => {
+                self.bufout
+                    .write_all(&format!("{}", g.to_incorrect()).into_bytes())?;
+                self.bufout
+                    .write_all(&format!(",{},{}\n", v1, v2).into_bytes())?;
+            }
+            LogInfo::LocalExtremum(g) => {
+                self.bufout.write_all(&format!("{}\n", g).into_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), TransProofError> {
+        self.bufout.flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), TransProofError> {
+        self.bufout.flush()?;
+        self.bufout.get_mut().finish()?;
+        Ok(())
+    }
+}
+
+/// Longest key used to name the files of a DirSink, leaving room for the suffix and the extension
+/// below the usual limit of 255 bytes of a file name.
+const MAX_KEY_LEN: usize = 200;
+
+/// Key naming the file of the graph with the given signature. Signatures longer than MAX_KEY_LEN
+/// are truncated and completed with their hash, after a '=' that graph6 signatures never contain.
+fn file_key(sig: String) -> String {
+    if sig.len() <= MAX_KEY_LEN {
+        return sig;
+    }
+    let mut hasher = DefaultHasher::new();
+    sig.hash(&mut hasher);
+    format!("{}={:016x}", &sig[..MAX_KEY_LEN - 17], hasher.finish())
+}
+
+/// Sink writing each result in its own file of a directory. The files are named after the
+/// signature of the resulting graph, see file_key, with a counter suffix if several results lead
+/// to the same graph or if the file already exists.
+pub struct DirSink {
+    dir: PathBuf,
+    extension: &'static str,
+    used: HashMap<String, usize>,
+}
+
+impl DirSink {
+    /// Creates the directory if it does not exist. The files are given the extension of the
+    /// format of the results.
+    pub fn new(dirname: &str, format: OutputFormat) -> Result<Self, TransProofError> {
+        fs::create_dir_all(dirname)?;
+        Ok(DirSink {
+            dir: PathBuf::from(dirname),
+            extension: format.extension(),
+            used: HashMap::new(),
+        })
+    }
+}
+
+impl ResultSink for DirSink {
+    fn accept(&mut self, log: LogInfo) -> Result<(), TransProofError> {
+        // The results are already canonized by apply_transfos.
+        let (sig, txt) = match log {
+            LogInfo::Transfo(t, s, _) => (format!("{}", t.final_graph()), s),
+            LogInfo::IncorrectTransfo {
+                result: g,
+                before: v1,
+                after: v2,
+                ..
+            } => (
+                format!("{}", g.final_graph()),
+                format!("{},{},{}", g.to_incorrect(), v1, v2),
+            ),
+            LogInfo::LocalExtremum(g) => (format!("{}", g), format!("{}", g)),
+        };
+        let key = file_key(sig);
+        // Graph6 signatures never contain '-' so the suffix cannot collide with another key.
+        let n = self.used.entry(key.clone()).or_insert(0);
+        let mut file = loop {
+            let filename = if *n == 0 {
+                format!("{}.{}", key, self.extension)
+            } else {
+                format!("{}-{}.{}", key, n, self.extension)
+            };
+            *n += 1;
+            // Never overwrites the results of a previous run in the same directory.
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(self.dir.join(filename))
+            {
+                Ok(file) => break file,
+                Err(ref e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+        };
+        file.write_all(&txt.into_bytes())?;
+        file.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+/// Sink used for dry runs. Drops every message, only the counts of run_sink remain.
+pub struct CountSink;
+
+impl ResultSink for CountSink {
+    fn accept(&mut self, _: LogInfo) -> Result<(), TransProofError> {
+        Ok(())
+    }
+}
+
+/// Sink keeping every message in memory, for the tests.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct VecSink {
+    pub logs: Vec<LogInfo>,
+}
+
+#[cfg(test)]
+impl ResultSink for VecSink {
+    fn accept(&mut self, log: LogInfo) -> Result<(), TransProofError> {
+        self.logs.push(log);
+        Ok(())
+    }
+}
+
+/// Statistics of a run.
+#[derive(Debug, Default, Serialize)]
+pub struct RunStats {
+    /// Number of results.
+    pub results: usize,
+    /// Number of results of each transformation, by index.
+    #[serde(skip)]
+    pub counts: Vec<usize>,
+    /// Number of results of each transformation, by name.
+    pub transformations: BTreeMap<String, usize>,
+    /// Number of distinct resulting graphs, if duplicates were dropped.
+    pub distinct: Option<usize>,
+    /// Duration of the run in seconds.
+    pub seconds: f64,
+}
+
+/// Hands the received messages to the sink until every sender is dropped and returns the statistics
+/// of the run, with the results counted by transformation index. The sink is flushed at least
+/// every flush_interval milliseconds if it is not 0, and progress is logged every progress results
+/// if it is not 0.
+pub fn run_sink<S>(
+    receiver: Receiver<LogInfo>,
+    sink: &mut S,
+    progress: usize,
+    flush_interval: u64,
+) -> Result<RunStats, TransProofError>
+where
+    S: ResultSink,
+{
+    let start = Instant::now();
+    let mut i = 0;
+    let mut counts = Vec::new();
+    let mut last_flush = Instant::now();
+    loop {
+        let log = if flush_interval == 0 {
+            match receiver.recv() {
+                Ok(log) => log,
+                Err(_) => break,
+            }
+        } else {
+            // Waits at most until the next flush is due.
+            let interval = Duration::from_millis(flush_interval);
+            let elapsed = last_flush.elapsed();
+            let received = if elapsed >= interval {
+                Err(RecvTimeoutError::Timeout)
+            } else {
+                receiver.recv_timeout(interval - elapsed)
+            };
+            match received {
+                Ok(log) => log,
+                Err(RecvTimeoutError::Timeout) => {
+                    sink.flush()?;
+                    last_flush = Instant::now();
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        };
+        match log {
+            LogInfo::Transfo(_, _, transfo) | LogInfo::IncorrectTransfo { transfo, .. } => {
+                i += 1;
+                count_transfo(&mut counts, transfo);
+                log_progress(i, progress, start);
+            }
+            LogInfo::LocalExtremum(_) => {}
+        }
+        sink.accept(log)?;
+    }
+    sink.finish()?;
+    log_done(i, start);
+    Ok(RunStats {
+        results: i,
+        counts,
+        seconds: start.elapsed().as_secs_f64(),
+        ..Default::default()
+    })
+}
+
+/// Increments the number of results of the transformation with the given index.
+fn count_transfo(counts: &mut Vec<usize>, transfo: usize) {
+    if counts.len() <= transfo {
+        counts.resize(transfo + 1, 0);
+    }
+    counts[transfo] += 1;
+}
+
+/// Logs the number of transformations handled so far every `interval` transformations.
+/// An interval of 0 disables it.
+fn log_progress(i: usize, interval: usize, start: Instant) {
+    if interval > 0 && i % interval == 0 {
+        let secs = start.elapsed().as_secs() as usize;
+        info!(
+            "{} transformation{} so far ({} second{})",
+            i,
+            plural(i),
+            secs,
+            plural(secs)
+        );
+    }
+}
+
+fn log_done(i: usize, start: Instant) {
+    let duration = start.elapsed();
+    info!("Done : {} transformation{}", i, plural(i));
+    let secs = duration.as_secs() as usize;
+    let millis = (duration.subsec_nanos() as usize) / (1e6 as usize);
+    info!(
+        "Took {} second{} and {} millisecond{}",
+        secs,
+        plural(secs),
+        millis,
+        plural(millis)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::format::from_g6;
+    use graph::transfos::remove_num_edges;
+    use std::path::Path;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn run_sink_collects_and_counts() {
+        let g = from_g6(&"Bw".to_string()).unwrap();
+        let h = remove_num_edges(&g, 1).pop().unwrap();
+        let (sender, receiver) = channel();
+        sender.send(LogInfo::Transfo(h.clone(), "a".to_string(), 0)).unwrap();
+        sender.send(LogInfo::Transfo(h.clone(), "b".to_string(), 2)).unwrap();
+        sender
+            .send(LogInfo::IncorrectTransfo {
+                result: h,
+                transfo: 2,
+                before: 1.0,
+                after: 2.0,
+            })
+            .unwrap();
+        sender.send(LogInfo::LocalExtremum(g)).unwrap();
+        drop(sender);
+
+        let mut sink = VecSink::default();
+        let stats = run_sink(receiver, &mut sink, 0, 0).unwrap();
+        assert_eq!(stats.results, 3);
+        assert_eq!(stats.counts, vec![1, 0, 2]);
+        assert_eq!(sink.logs.len(), 4);
+        assert!(matches!(&sink.logs[0], LogInfo::Transfo(_, s, 0) if s == "a"));
+        assert!(matches!(&sink.logs[1], LogInfo::Transfo(_, s, 2) if s == "b"));
+        assert!(matches!(
+            sink.logs[2],
+            LogInfo::IncorrectTransfo { transfo: 2, .. }
+        ));
+        assert!(matches!(sink.logs[3], LogInfo::LocalExtremum(_)));
+    }
+
+    /// Creates an empty directory for a test in the temporary directory.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("transrust-{}-{}", name, std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        dir
+    }
+
+    /// Names of the files of the directory, sorted.
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn dir_sink_suffixes_duplicates() {
+        let dir = test_dir("dir_sink");
+        let g = from_g6(&"Bw".to_string()).unwrap();
+        let mut sink = DirSink::new(dir.to_str().unwrap(), OutputFormat::Jsonl).unwrap();
+        sink.accept(LogInfo::LocalExtremum(g.clone())).unwrap();
+        sink.accept(LogInfo::LocalExtremum(g)).unwrap();
+        let names = file_names(&dir);
+        let content = fs::read_to_string(dir.join("Bw-1.jsonl")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(names, vec!["Bw-1.jsonl", "Bw.jsonl"]);
+        assert_eq!(content, "Bw\n");
+    }
+
+    #[test]
+    fn dir_sink_keeps_existing_files() {
+        let dir = test_dir("dir_sink_existing");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("Bw.csv"), "previous\n").unwrap();
+        fs::write(dir.join("Bw-1.csv"), "previous\n").unwrap();
+        let g = from_g6(&"Bw".to_string()).unwrap();
+        let mut sink = DirSink::new(dir.to_str().unwrap(), OutputFormat::Csv).unwrap();
+        sink.accept(LogInfo::LocalExtremum(g)).unwrap();
+        let names = file_names(&dir);
+        let previous = fs::read_to_string(dir.join("Bw.csv")).unwrap();
+        let content = fs::read_to_string(dir.join("Bw-2.csv")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(names, vec!["Bw-1.csv", "Bw-2.csv", "Bw.csv"]);
+        assert_eq!(previous, "previous\n");
+        assert_eq!(content, "Bw\n");
+    }
+
+    #[test]
+    fn dir_sink_shortens_long_signatures() {
+        let dir = test_dir("dir_sink_long");
+        // Graph without edges on 60 vertices.
+        let sig = format!("{{{}", "?".repeat(295));
+        let g = from_g6(&sig).unwrap();
+        let mut sink = DirSink::new(dir.to_str().unwrap(), OutputFormat::Csv).unwrap();
+        sink.accept(LogInfo::LocalExtremum(g.clone())).unwrap();
+        sink.accept(LogInfo::LocalExtremum(g)).unwrap();
+        let names = file_names(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        let key = file_key(sig.clone());
+        assert_eq!(key.len(), MAX_KEY_LEN);
+        assert!(key.starts_with(&sig[..MAX_KEY_LEN - 17]));
+        assert_eq!(names, vec![format!("{}-1.csv", key), format!("{}.csv", key)]);
+    }
+
+    #[test]
+    fn run_sink_does_not_count_local_extrema() {
+        let g = from_g6(&"Bw".to_string()).unwrap();
+        let (sender, receiver) = channel();
+        sender.send(LogInfo::LocalExtremum(g.clone())).unwrap();
+        sender.send(LogInfo::LocalExtremum(g)).unwrap();
+        drop(sender);
+
+        let mut sink = VecSink::default();
+        let stats = run_sink(receiver, &mut sink, 0, 0).unwrap();
+        assert_eq!(stats.results, 0);
+        assert!(stats.counts.is_empty());
+        assert_eq!(sink.logs.len(), 2);
+    }
+}
@@ -128,6 +128,12 @@ pub fn print_transfos() {
 
 pub trait Transformation: Send + Sync {
     fn apply(&self, input: &GraphNauty) -> Vec<GraphTransformation>;
+
+    /// Same as apply but each result comes with the index of the transformation that produced
+    /// it. A single transformation always has index 0.
+    fn apply_indexed(&self, input: &GraphNauty) -> Vec<(usize, GraphTransformation)> {
+        self.apply(input).into_iter().map(|r| (0, r)).collect()
+    }
 }
 
 impl<F> Transformation for F
@@ -156,6 +162,13 @@ impl Transformation for TransfoVec {
             .flat_map(|x| x.apply(input).into_iter())
             .collect()
     }
+
+    fn apply_indexed(&self, input: &GraphNauty) -> Vec<(usize, GraphTransformation)> {
+        self.iter()
+            .enumerate()
+            .flat_map(|(id, x)| x.apply(input).into_iter().map(move |r| (id, r)))
+            .collect()
+    }
 }
 
 //#[derive(Clone)]
@@ -1,4 +1,12 @@
+use crate::errors::TransProofError;
 use graph::transfo_result::GraphTransformation;
+use graph::GraphIter;
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 /// Returns "s" if i is different from 1 and an empty string otherwise.
 pub fn plural(i: usize) -> String {
@@ -9,23 +17,284 @@ pub fn plural(i: usize) -> String {
     }
 }
 
-pub fn as_filter<'a, F, S>(filter: F, name: S) -> Box<dyn Fn(&GraphTransformation) -> Result<String, ()> + 'a>
-    where F: Fn(&GraphTransformation) -> bool + 'a,
-          S: Fn(&GraphTransformation) -> String + 'a
+/// Filter on the results of the transformations. Returns a name for the results it accepts.
+pub type Filter<'a> = Arc<dyn Fn(&GraphTransformation) -> Result<String, ()> + Send + Sync + 'a>;
+
+pub fn as_filter<'a, F, S>(filter: F, name: S) -> Filter<'a>
+    where F: Fn(&GraphTransformation) -> bool + Send + Sync + 'a,
+          S: Fn(&GraphTransformation) -> String + Send + Sync + 'a
 {
-    Box::new(move |x| if filter(x) { Ok(name(x)) } else { Err(()) })
+    Arc::new(move |x| if filter(x) { Ok(name(x)) } else { Err(()) })
 }
 
-pub fn combine_filters<'a, F, G>(f: F, g: G) -> Box<dyn Fn(&GraphTransformation) -> Result<String, ()> + 'a>
-    where F: Fn(&GraphTransformation) -> Result<String, ()> + 'a,
-          G: Fn(&GraphTransformation) -> Result<String, ()> + 'a
-{
-    Box::new(move |x| match f(x) {
+pub fn combine_filters<'a>(f: Filter<'a>, g: Filter<'a>) -> Filter<'a> {
+    Arc::new(move |x| match f(x) {
         Err(_) => g(x),
         Ok(s) => Ok(s),
     })
 }
 
+/// Accepts the results accepted by all the filters and names them using the last one.
+pub fn all_of<'a>(filters: Vec<Filter<'a>>) -> Filter<'a> {
+    Arc::new(move |x| {
+        let mut name = String::new();
+        for f in filters.iter() {
+            name = f(x)?;
+        }
+        Ok(name)
+    })
+}
+
 pub fn trash_node(_: &GraphTransformation) -> Result<String, ()> {
     Ok("TRASH".to_string())
 }
+
+pub type Predicate = Box<dyn Fn(&GraphTransformation) -> bool + Send + Sync>;
+
+/// Parses a filter of the form name:value into a predicate over the result of a transformation.
+/// The available filters are min_vertices, max_vertices, min_edges and max_edges.
+pub fn parse_filter(filter: &str) -> Result<Predicate, TransProofError> {
+    let invalid = || TransProofError::InvalidFilter(filter.to_string());
+    let mut parts = filter.trim().splitn(2, ':');
+    let name = parts.next().ok_or_else(invalid)?.to_lowercase();
+    let value: u64 = parts
+        .next()
+        .ok_or_else(invalid)?
+        .trim()
+        .parse()
+        .map_err(|_| invalid())?;
+    match name.as_str() {
+        "min_vertices" => Ok(Box::new(move |x: &GraphTransformation| {
+            x.final_graph().order() >= value
+        })),
+        "max_vertices" => Ok(Box::new(move |x: &GraphTransformation| {
+            x.final_graph().order() <= value
+        })),
+        "min_edges" => Ok(Box::new(move |x: &GraphTransformation| {
+            x.final_graph().size() >= value
+        })),
+        "max_edges" => Ok(Box::new(move |x: &GraphTransformation| {
+            x.final_graph().size() <= value
+        })),
+        _ => Err(invalid()),
+    }
+}
+
+/// Reader failing with an error once more than `limit` bytes have been read from `inner`.
+pub struct LimitedReader<R> {
+    inner: R,
+    limit: usize,
+    read: usize,
+}
+
+impl<R> LimitedReader<R> {
+    pub fn new(inner: R, limit: usize) -> Self {
+        LimitedReader {
+            inner,
+            limit,
+            read: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let available = self.fill_buf()?;
+            let n = min(available.len(), buf.len());
+            buf[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for LimitedReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let remaining = self.limit - self.read;
+        let available = self.inner.fill_buf()?;
+        if remaining == 0 && !available.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "Input is larger than the limit of {} byte{}.",
+                    self.limit,
+                    plural(self.limit)
+                ),
+            ));
+        }
+        let n = min(available.len(), remaining);
+        Ok(&available[..n])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.read += amt;
+    }
+}
+
+/// Reads the files one after the other, opening each one only once the previous one is read.
+/// A newline is added after each file so that its last line is not merged with the first line of
+/// the next one.
+struct FilesReader {
+    files: VecDeque<PathBuf>,
+    current: Option<File>,
+}
+
+impl Read for FilesReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.current.as_mut() {
+                Some(file) => {
+                    let n = file.read(buf)?;
+                    if n > 0 {
+                        return Ok(n);
+                    }
+                    self.current = None;
+                    buf[0] = b'\n';
+                    return Ok(1);
+                }
+                None => match self.files.pop_front() {
+                    Some(path) => self.current = Some(File::open(path)?),
+                    None => return Ok(0),
+                },
+            }
+        }
+    }
+}
+
+/// Opens the input file. If the path is a directory, the files it contains are read one after the
+/// other in the order of their names.
+pub fn open_input(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    if !path.is_dir() {
+        return Ok(Box::new(BufReader::new(File::open(path)?)));
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let file = entry?.path();
+        if file.is_file() {
+            files.push(file);
+        }
+    }
+    files.sort();
+    Ok(Box::new(BufReader::new(FilesReader {
+        files: files.into(),
+        current: None,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::format::from_g6;
+    use graph::transfos::remove_edge;
+
+    /// Triangle without one of its edges: 3 vertices and 2 edges.
+    fn path() -> GraphTransformation {
+        let g = from_g6(&"Bw".to_string()).unwrap();
+        remove_edge(&g).pop().unwrap()
+    }
+
+    #[test]
+    fn all_of_needs_every_filter() {
+        let h = path();
+        let accept = || as_filter(|_| true, |_| "accept".to_string());
+        let reject = || as_filter(|x| x.final_graph().order() > 3, |_| "reject".to_string());
+        let named = || as_filter(|_| true, |_| "named".to_string());
+        assert_eq!(all_of(vec![accept(), named()])(&h), Ok("named".to_string()));
+        assert_eq!(all_of(vec![named(), accept()])(&h), Ok("accept".to_string()));
+        assert_eq!(all_of(vec![accept(), reject()])(&h), Err(()));
+        assert_eq!(all_of(vec![reject(), accept()])(&h), Err(()));
+        assert_eq!(all_of(Vec::new())(&h), Ok(String::new()));
+    }
+
+    #[test]
+    fn parse_filter_bounds() {
+        let h = path();
+        assert!(parse_filter("min_vertices:3").unwrap()(&h));
+        assert!(!parse_filter("min_vertices:4").unwrap()(&h));
+        assert!(parse_filter("max_vertices:3").unwrap()(&h));
+        assert!(!parse_filter("max_vertices:2").unwrap()(&h));
+        assert!(parse_filter("min_edges:2").unwrap()(&h));
+        assert!(!parse_filter("min_edges:3").unwrap()(&h));
+        assert!(parse_filter(" MAX_EDGES: 2 ").unwrap()(&h));
+        assert!(!parse_filter("max_edges:1").unwrap()(&h));
+    }
+
+    #[test]
+    fn parse_filter_rejects_invalid_filters() {
+        for filter in &["min_order:3", "min_edges", "min_edges:", "min_edges:two", ":3"] {
+            assert!(matches!(
+                parse_filter(filter),
+                Err(TransProofError::InvalidFilter(ref f)) if f == filter
+            ));
+        }
+    }
+
+    #[test]
+    fn limited_reader_reads_up_to_the_limit() {
+        let mut rdr = LimitedReader::new(&b"abcd"[..], 4);
+        let mut s = String::new();
+        rdr.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "abcd");
+    }
+
+    #[test]
+    fn limited_reader_fails_past_the_limit() {
+        let mut rdr = LimitedReader::new(&b"abcde"[..], 4);
+        let mut buf = [0; 3];
+        assert_eq!(rdr.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"abc");
+        assert_eq!(rdr.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], b'd');
+        assert_eq!(rdr.read(&mut buf).unwrap_err().kind(), io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn limited_reader_buffered_reads() {
+        let mut rdr = LimitedReader::new(&b"abcde"[..], 4);
+        assert_eq!(rdr.fill_buf().unwrap(), &b"abcd"[..]);
+        rdr.consume(2);
+        assert_eq!(rdr.fill_buf().unwrap(), &b"cd"[..]);
+        rdr.consume(2);
+        assert_eq!(rdr.fill_buf().unwrap_err().kind(), io::ErrorKind::Other);
+    }
+
+    /// Creates an empty directory for a test in the temporary directory.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("transrust-{}-{}", name, std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn open_input_reads_directory_in_order() {
+        let dir = test_dir("open_input");
+        fs::write(dir.join("b"), "3\n").unwrap();
+        fs::write(dir.join("a"), "1\n2").unwrap();
+        fs::create_dir(dir.join("c")).unwrap();
+        fs::write(dir.join("c").join("d"), "4\n").unwrap();
+        let mut s = String::new();
+        open_input(&dir).unwrap().read_to_string(&mut s).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        // A newline is added after each file, even the ones ending with one.
+        assert_eq!(s, "1\n2\n3\n\n");
+    }
+
+    #[test]
+    fn open_input_reads_file() {
+        let dir = test_dir("open_input_file");
+        fs::write(dir.join("a"), "1\n2").unwrap();
+        let mut s = String::new();
+        open_input(&dir.join("a")).unwrap().read_to_string(&mut s).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(s, "1\n2");
+    }
+}